@@ -0,0 +1,49 @@
+//! Low-level building blocks for hashing individual subtrees, for crates
+//! that need to verify or reconstruct a BLAKE3 tree incrementally (for
+//! example, a Bao-style verified-streaming encoder or decoder) rather than
+//! feed a whole input through [`crate::Hasher`].
+//!
+//! These mirror the private `ChunkState`/`parent_cv` plumbing that
+//! [`crate::Hasher::update`] and [`crate::Hasher::update_rayon`] are built
+//! on, so a caller can hash one chunk at a known offset, or combine two
+//! child chaining values into their parent, and get results that compose
+//! into the exact same tree.
+//!
+//! Both functions take a `flags` parameter for the hashing mode (`0` for the
+//! regular hash function, or [`crate::KEYED_HASH`] / [`crate::DERIVE_KEY`]);
+//! OR in [`crate::ROOT`] when the node being hashed is the root of the
+//! overall tree (a lone chunk for single-chunk inputs, or the final parent
+//! merge otherwise) to get the finalized 32-byte hash instead of a chaining
+//! value to be combined further.
+
+use crate::{ChunkState, CHUNK_LEN, KEY_LEN, OUT_LEN};
+
+/// Hash a single chunk of up to [`CHUNK_LEN`] bytes at `chunk_counter`,
+/// returning its chaining value (or, with [`crate::ROOT`] set in `flags`,
+/// the finalized hash, when this chunk is the entire input).
+pub fn chunk_cv(input: &[u8], chunk_counter: u64, key: &[u8; KEY_LEN], flags: u8) -> [u8; OUT_LEN] {
+    debug_assert!(input.len() <= CHUNK_LEN);
+    // `ROOT` only ever applies to the chunk's last block, never to every
+    // block the way the rest of `flags` does, so it can't be baked into the
+    // `ChunkState`'s own flags (which `update` mixes into every block as it
+    // goes). Build the chunk with the mode flags alone, and OR `ROOT` in
+    // only on the finalized output, the same way `OutputReader` applies it
+    // at read time rather than during accumulation.
+    let mut state = ChunkState::new(key, chunk_counter, flags & !crate::ROOT);
+    state.update(input);
+    let mut output = state.output();
+    output.flags |= flags & crate::ROOT;
+    output.chaining_value()
+}
+
+/// Combine a left and right chaining value into their parent's chaining
+/// value (or, with [`crate::ROOT`] set in `flags`, the finalized hash, when
+/// this is the final merge at the top of the tree).
+pub fn parent_cv(
+    left_cv: &[u8; OUT_LEN],
+    right_cv: &[u8; OUT_LEN],
+    key: &[u8; KEY_LEN],
+    flags: u8,
+) -> [u8; OUT_LEN] {
+    crate::parent_cv(left_cv, right_cv, key, flags)
+}
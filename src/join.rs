@@ -0,0 +1,52 @@
+//! Recursive subtree hashing for [`crate::Hasher::update_rayon`]. The tree
+//! structure the incremental `Hasher` already maintains (see `left_len` and
+//! `push_cv` in the crate root) decomposes cleanly into independent
+//! left/right halves, which is exactly what lets this parallelize with
+//! `rayon::join` instead of a bespoke thread pool.
+
+use crate::{parent_cv, ChunkState, CHUNK_LEN, KEY_LEN, OUT_LEN};
+
+/// Above this many input bytes, `hash_subtree` spawns its two halves on the
+/// `rayon` pool instead of recursing in the calling thread. Below it, the
+/// overhead of `rayon::join` isn't worth paying.
+pub(crate) const RAYON_JOIN_THRESHOLD: usize = 16 * CHUNK_LEN;
+
+fn hash_chunk(chunk: &[u8], key: &[u8; KEY_LEN], chunk_counter: u64, flags: u8) -> [u8; OUT_LEN] {
+    let mut state = ChunkState::new(key, chunk_counter, flags);
+    state.update(chunk);
+    state.output().chaining_value()
+}
+
+/// Hash `input`, a byte slice starting at chunk `chunk_counter`, into the
+/// chaining value of the subtree it forms. `input` need not be a power of
+/// two number of chunks; like the sequential `Hasher`, it splits unevenly at
+/// `left_len`, always making the left side a complete, perfectly balanced
+/// tree.
+pub(crate) fn hash_subtree(
+    input: &[u8],
+    key: &[u8; KEY_LEN],
+    chunk_counter: u64,
+    flags: u8,
+) -> [u8; OUT_LEN] {
+    debug_assert!(!input.is_empty());
+    if input.len() <= CHUNK_LEN {
+        return hash_chunk(input, key, chunk_counter, flags);
+    }
+
+    let split = crate::left_len(input.len());
+    let (left_input, right_input) = input.split_at(split);
+    let right_chunk_counter = chunk_counter + (split / CHUNK_LEN) as u64;
+
+    let (left_cv, right_cv) = if input.len() > RAYON_JOIN_THRESHOLD {
+        rayon::join(
+            || hash_subtree(left_input, key, chunk_counter, flags),
+            || hash_subtree(right_input, key, right_chunk_counter, flags),
+        )
+    } else {
+        (
+            hash_subtree(left_input, key, chunk_counter, flags),
+            hash_subtree(right_input, key, right_chunk_counter, flags),
+        )
+    };
+    parent_cv(&left_cv, &right_cv, key, flags)
+}
@@ -0,0 +1,595 @@
+//! An implementation of the BLAKE3 cryptographic hash function.
+//!
+//! # Example
+//!
+//! ```
+//! let hash = blake3::hash(b"hello world");
+//! # let _ = hash;
+//! ```
+//!
+//! The [`Hasher`] type supports incremental updates, keyed hashing
+//! (`new_keyed`), key derivation (`new_derive_key`), and extendable output
+//! ([`Hasher::finalize_xof_reader`]).
+//!
+//! This crate supports `no_std` when built without default features; the
+//! `std` feature, on by default, enables convenience APIs that need the
+//! standard library (file I/O, `Seek`, and memory-mapped hashing via
+//! [`Hasher::update_mmap`]). Enable the `rayon`
+//! feature for [`Hasher::update_rayon`], which hashes large inputs across a
+//! thread pool.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use arrayref::array_ref;
+use arrayvec::ArrayVec;
+
+mod portable;
+
+pub mod guts;
+
+#[cfg(feature = "rayon")]
+mod join;
+
+#[cfg(feature = "std")]
+mod mmap;
+
+#[cfg(test)]
+mod test;
+
+/// The number of bytes in a [`Hash`], a key, or a chaining value.
+pub const OUT_LEN: usize = 32;
+/// The number of bytes in a key, for [`Hasher::new_keyed`] and
+/// [`Hasher::new_derive_key`].
+pub const KEY_LEN: usize = 32;
+/// The number of bytes in a single compression function input block.
+pub const BLOCK_LEN: usize = 64;
+/// The number of bytes in a single chunk, before the tree structure kicks in.
+pub const CHUNK_LEN: usize = 1024;
+
+pub const CHUNK_START: u8 = 1 << 0;
+pub const CHUNK_END: u8 = 1 << 1;
+pub const PARENT: u8 = 1 << 2;
+pub const ROOT: u8 = 1 << 3;
+pub const KEYED_HASH: u8 = 1 << 4;
+pub const DERIVE_KEY: u8 = 1 << 5;
+
+pub(crate) const IV: [u32; 8] = [
+    0x6A09_E667,
+    0xBB67_AE85,
+    0x3C6E_F372,
+    0xA54F_F53A,
+    0x510E_527F,
+    0x9B05_688C,
+    0x1F83_D9AB,
+    0x5BE0_CD19,
+];
+
+pub(crate) const MSG_PERMUTATION: [usize; 16] =
+    [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+pub(crate) fn offset_low(offset: u64) -> u32 {
+    offset as u32
+}
+
+pub(crate) fn offset_high(offset: u64) -> u32 {
+    (offset >> 32) as u32
+}
+
+/// The largest power of two that's less than or equal to `n`. `n == 0` is a
+/// degenerate case that returns 1; callers only rely on this for chunk
+/// counts, which are always at least 1 by the time it's called.
+pub(crate) fn largest_power_of_two_leq(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n | 1).leading_zeros())
+}
+
+/// Given the length of a tree with more than one chunk, return the length of
+/// its left subtree. The left subtree is always a complete, perfectly
+/// balanced tree, so this computes the largest power-of-two number of full
+/// chunks that still leaves at least one byte for the right side.
+pub(crate) fn left_len(content_len: usize) -> usize {
+    debug_assert!(content_len > CHUNK_LEN);
+    let full_chunks = (content_len - 1) / CHUNK_LEN;
+    largest_power_of_two_leq(full_chunks) * CHUNK_LEN
+}
+
+fn iv_bytes() -> [u8; KEY_LEN] {
+    let mut out = [0u8; KEY_LEN];
+    for (i, word) in IV.iter().enumerate() {
+        out[4 * i..][..4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+pub(crate) fn parent_cv(
+    left_cv: &[u8; OUT_LEN],
+    right_cv: &[u8; OUT_LEN],
+    key: &[u8; KEY_LEN],
+    flags: u8,
+) -> [u8; OUT_LEN] {
+    let mut block = [0u8; BLOCK_LEN];
+    block[..OUT_LEN].copy_from_slice(left_cv);
+    block[OUT_LEN..].copy_from_slice(right_cv);
+    let wide = portable::compress(key, &block, BLOCK_LEN as u8, 0, flags | PARENT);
+    *array_ref!(wide, 0, OUT_LEN)
+}
+
+/// A finalized BLAKE3 output. Implements constant-time equality.
+#[derive(Clone, Copy, Debug)]
+pub struct Hash([u8; OUT_LEN]);
+
+impl Hash {
+    pub fn as_bytes(&self) -> &[u8; OUT_LEN] {
+        &self.0
+    }
+}
+
+impl From<[u8; OUT_LEN]> for Hash {
+    fn from(bytes: [u8; OUT_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Hash> for [u8; OUT_LEN] {
+    fn from(hash: Hash) -> Self {
+        hash.0
+    }
+}
+
+impl PartialEq for Hash {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq::constant_time_eq_32(&self.0, &other.0)
+    }
+}
+
+impl Eq for Hash {}
+
+impl PartialEq<[u8; OUT_LEN]> for Hash {
+    fn eq(&self, other: &[u8; OUT_LEN]) -> bool {
+        constant_time_eq::constant_time_eq_32(&self.0, other)
+    }
+}
+
+// A node that's ready to be turned into output bytes, either the regular
+// 32-byte chaining value or, at the root, an arbitrarily long XOF stream.
+// Keeping this separate from `ChunkState`/`Hasher` means both `finalize` and
+// `finalize_xof_reader` can share the same root-compression logic.
+#[derive(Clone)]
+pub(crate) struct Output {
+    input_cv: [u8; OUT_LEN],
+    block: [u8; BLOCK_LEN],
+    block_len: u8,
+    counter: u64,
+    flags: u8,
+}
+
+impl Output {
+    pub(crate) fn chaining_value(&self) -> [u8; OUT_LEN] {
+        let wide = portable::compress(
+            &self.input_cv,
+            &self.block,
+            self.block_len,
+            self.counter,
+            self.flags,
+        );
+        *array_ref!(wide, 0, OUT_LEN)
+    }
+
+    // Run the root compression at a given output block counter, producing 64
+    // bytes of keystream. `block_counter` is the output position divided by
+    // `BLOCK_LEN`, and it replaces the node's own `counter` in the
+    // compression, so each output block depends only on its own index, which
+    // is what lets this be called at an arbitrary, non-sequential offset.
+    fn root_compression_block(&self, block_counter: u64) -> [u8; 64] {
+        portable::compress(
+            &self.input_cv,
+            &self.block,
+            self.block_len,
+            block_counter,
+            self.flags | ROOT,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ChunkState {
+    cv: [u8; OUT_LEN],
+    pub(crate) chunk_counter: u64,
+    buf: [u8; BLOCK_LEN],
+    buf_len: u8,
+    blocks_compressed: u8,
+    flags: u8,
+}
+
+impl ChunkState {
+    pub(crate) fn new(key: &[u8; KEY_LEN], chunk_counter: u64, flags: u8) -> Self {
+        Self {
+            cv: *key,
+            chunk_counter,
+            buf: [0; BLOCK_LEN],
+            buf_len: 0,
+            blocks_compressed: 0,
+            flags,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        BLOCK_LEN * self.blocks_compressed as usize + self.buf_len as usize
+    }
+
+    fn start_flag(&self) -> u8 {
+        if self.blocks_compressed == 0 {
+            CHUNK_START
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            if self.buf_len as usize == BLOCK_LEN {
+                let block_flags = self.flags | self.start_flag();
+                let wide = portable::compress(
+                    &self.cv,
+                    &self.buf,
+                    BLOCK_LEN as u8,
+                    self.chunk_counter,
+                    block_flags,
+                );
+                self.cv = *array_ref!(wide, 0, OUT_LEN);
+                self.blocks_compressed += 1;
+                self.buf = [0; BLOCK_LEN];
+                self.buf_len = 0;
+            }
+            let want = BLOCK_LEN - self.buf_len as usize;
+            let take = want.min(input.len());
+            self.buf[self.buf_len as usize..][..take].copy_from_slice(&input[..take]);
+            self.buf_len += take as u8;
+            input = &input[take..];
+        }
+    }
+
+    pub(crate) fn output(&self) -> Output {
+        Output {
+            input_cv: self.cv,
+            block: self.buf,
+            block_len: self.buf_len,
+            counter: self.chunk_counter,
+            flags: self.flags | self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// An incremental BLAKE3 hasher, supporting the default, keyed, and
+/// key-derivation modes.
+#[derive(Clone)]
+pub struct Hasher {
+    pub(crate) key: [u8; KEY_LEN],
+    pub(crate) chunk_state: ChunkState,
+    cv_stack: ArrayVec<[u8; OUT_LEN], 54>,
+    pub(crate) flags: u8,
+}
+
+impl Hasher {
+    fn new_internal(key: [u8; KEY_LEN], flags: u8) -> Self {
+        Self {
+            key,
+            chunk_state: ChunkState::new(&key, 0, flags),
+            cv_stack: ArrayVec::new(),
+            flags,
+        }
+    }
+
+    /// Construct a new `Hasher` for the regular hash function.
+    pub fn new() -> Self {
+        Self::new_internal(iv_bytes(), 0)
+    }
+
+    /// Construct a new `Hasher` for the keyed hash function.
+    pub fn new_keyed(key: &[u8; KEY_LEN]) -> Self {
+        Self::new_internal(*key, KEYED_HASH)
+    }
+
+    /// Construct a new `Hasher` for the key derivation function.
+    pub fn new_derive_key(key: &[u8; KEY_LEN]) -> Self {
+        Self::new_internal(*key, DERIVE_KEY)
+    }
+
+    /// Reset the `Hasher` to its initial state, as it was right after
+    /// construction, while keeping the configured key material and mode
+    /// (regular, `new_keyed`, or `new_derive_key`). This is cheaper than
+    /// building a fresh `Hasher`, since it reuses the same allocation
+    /// instead of re-running key setup.
+    pub fn reset(&mut self) -> &mut Self {
+        self.chunk_state = ChunkState::new(&self.key, 0, self.flags);
+        self.cv_stack.clear();
+        self
+    }
+
+    /// Push the chaining value of a just-completed subtree spanning
+    /// `subtree_chunks` chunks (a power of two; 1 for a single chunk), whose
+    /// last chunk is `chunk_counter`, onto the CV stack.
+    ///
+    /// The stack is a standard binary counter over *subtree-sized* units:
+    /// since `chunk_counter + 1` is always a multiple of `subtree_chunks`
+    /// (the caller only ever completes a subtree once every chunk below it
+    /// is accounted for), the stack can't hold any entry smaller than
+    /// `subtree_chunks` at this point, so counting in units of
+    /// `subtree_chunks` and merging while that count is even is exactly the
+    /// single-chunk trailing-zero merge, scaled up.
+    pub(crate) fn push_cv(&mut self, new_cv: [u8; OUT_LEN], chunk_counter: u64, subtree_chunks: u64) {
+        let mut new_cv = new_cv;
+        let mut total_units = chunk_counter / subtree_chunks + 1;
+        while total_units & 1 == 0 {
+            let left_cv = self.cv_stack.pop().expect("stack is never empty here");
+            new_cv = parent_cv(&left_cv, &new_cv, &self.key, self.flags);
+            total_units >>= 1;
+        }
+        self.cv_stack.push(new_cv);
+    }
+
+    /// If the in-progress chunk is full, push it and start a fresh one.
+    /// A full chunk is left unflushed as long as no more input has shown up
+    /// behind it, since until then it might still turn out to be the final
+    /// chunk of the whole message, which `final_output` finalizes with
+    /// `ROOT` instead of pushing.
+    fn flush_full_chunk_state(&mut self) {
+        if self.chunk_state.len() == CHUNK_LEN {
+            let chunk_cv = self.chunk_state.output().chaining_value();
+            let chunk_counter = self.chunk_state.chunk_counter;
+            self.push_cv(chunk_cv, chunk_counter, 1);
+            self.chunk_state = ChunkState::new(&self.key, chunk_counter + 1, self.flags);
+        }
+    }
+
+    /// Add input bytes to the hash state. Can be called any number of times.
+    pub fn update(&mut self, mut input: &[u8]) -> &mut Self {
+        while !input.is_empty() {
+            self.flush_full_chunk_state();
+            let want = CHUNK_LEN - self.chunk_state.len();
+            let take = want.min(input.len());
+            self.chunk_state.update(&input[..take]);
+            input = &input[take..];
+        }
+        self
+    }
+
+    /// Like [`Hasher::update`], but hashes large inputs across a `rayon`
+    /// thread pool. The result is bit-for-bit identical to `update`,
+    /// regardless of how many threads are available or how the caller
+    /// chunks up their calls.
+    #[cfg(feature = "rayon")]
+    pub fn update_rayon(&mut self, mut input: &[u8]) -> &mut Self {
+        // Top up the chunk already in progress, the same way `update` would,
+        // so that the rest of `input` starts on a chunk boundary. If that
+        // completes the chunk and more input follows, flush it explicitly:
+        // `self.chunk_state.chunk_counter` below needs to already reflect
+        // every chunk that's actually been pushed, which wouldn't be true if
+        // a full-but-unflushed chunk were left sitting behind it.
+        if self.chunk_state.len() > 0 {
+            let want = CHUNK_LEN - self.chunk_state.len();
+            let take = want.min(input.len());
+            self.update(&input[..take]);
+            input = &input[take..];
+        }
+        if !input.is_empty() {
+            self.flush_full_chunk_state();
+        }
+
+        // Peel off the largest batch of complete chunks that's both a power
+        // of two and aligned with the existing stack, hash it as one subtree
+        // (recursively, and in parallel above `join::RAYON_JOIN_THRESHOLD`),
+        // and merge it into the stack in one step via `push_cv`'s
+        // trailing-zero merge, generalized to count whole batches instead of
+        // single chunks. The batch always leaves at least one byte behind in
+        // `rest`, the same way `left_len` always leaves at least one byte for
+        // the right subtree: that trailing chunk can't be hashed
+        // yet, because we don't know until the loop (and the final
+        // `self.update(input)` below) runs out of input whether it's the
+        // true last chunk of the whole message, which would need the `ROOT`
+        // flag instead of being pushed onto the stack.
+        while input.len() > CHUNK_LEN {
+            let total_chunks_before = self.chunk_state.chunk_counter;
+            let alignment_cap = if total_chunks_before == 0 {
+                usize::MAX
+            } else {
+                1 << total_chunks_before.trailing_zeros()
+            };
+            let max_batch_chunks = (input.len() - 1) / CHUNK_LEN;
+            let batch_chunks = largest_power_of_two_leq(max_batch_chunks).min(alignment_cap);
+            let batch_len = batch_chunks * CHUNK_LEN;
+            let (batch, rest) = input.split_at(batch_len);
+
+            let batch_cv = join::hash_subtree(batch, &self.key, total_chunks_before, self.flags);
+            let last_chunk_counter = total_chunks_before + batch_chunks as u64 - 1;
+            self.push_cv(batch_cv, last_chunk_counter, batch_chunks as u64);
+            self.chunk_state = ChunkState::new(&self.key, last_chunk_counter + 1, self.flags);
+            input = rest;
+        }
+
+        self.update(input)
+    }
+
+    /// Hash the contents of the file at `path`, equivalent to reading it and
+    /// passing the bytes to [`Hasher::update`].
+    ///
+    /// Files at least as large as an internal size threshold are
+    /// memory-mapped, which avoids the read-syscall/copy loop that repeated
+    /// `update` calls would otherwise force; smaller or unmappable files
+    /// (for example, named pipes) fall back to buffered reads. When the
+    /// `rayon` feature is enabled, mapped files are fed through
+    /// [`Hasher::update_rayon`] instead of `update`.
+    #[cfg(feature = "std")]
+    pub fn update_mmap(&mut self, path: impl AsRef<std::path::Path>) -> std::io::Result<&mut Self> {
+        let file = std::fs::File::open(path.as_ref())?;
+        if let Some(map) = mmap::maybe_memmap_file(&file)? {
+            #[cfg(feature = "rayon")]
+            self.update_rayon(&map);
+            #[cfg(not(feature = "rayon"))]
+            self.update(&map);
+        } else {
+            self.update_reader(file)?;
+        }
+        Ok(self)
+    }
+
+    // The buffered-read fallback for `update_mmap`, used for files that are
+    // too small to be worth mapping, or that can't be mapped at all.
+    #[cfg(feature = "std")]
+    fn update_reader(&mut self, mut reader: impl std::io::Read) -> std::io::Result<&mut Self> {
+        let mut buf = [0u8; 16 * 1024];
+        loop {
+            let bytes_read = reader.read(&mut buf)?;
+            if bytes_read == 0 {
+                break;
+            }
+            self.update(&buf[..bytes_read]);
+        }
+        Ok(self)
+    }
+
+    // Merge the final chunk with every subtree left on the stack, from the
+    // most to the least recently pushed, producing the output node for the
+    // root of the whole tree.
+    fn final_output(&self) -> Output {
+        let mut output = self.chunk_state.output();
+        let mut parent_nodes_remaining = self.cv_stack.len();
+        while parent_nodes_remaining > 0 {
+            parent_nodes_remaining -= 1;
+            let mut block = [0u8; BLOCK_LEN];
+            block[..OUT_LEN].copy_from_slice(&self.cv_stack[parent_nodes_remaining]);
+            block[OUT_LEN..].copy_from_slice(&output.chaining_value());
+            output = Output {
+                input_cv: self.key,
+                block,
+                block_len: BLOCK_LEN as u8,
+                counter: 0,
+                flags: self.flags | PARENT,
+            };
+        }
+        output
+    }
+
+    /// Finalize the hash state and return the [`Hash`].
+    pub fn finalize(&self) -> Hash {
+        let mut bytes = [0u8; OUT_LEN];
+        self.finalize_xof(&mut bytes);
+        Hash(bytes)
+    }
+
+    /// Finalize the hash state and fill `out` with extended output, starting
+    /// at position 0. For random access into the output stream, use
+    /// [`Hasher::finalize_xof_reader`] instead.
+    pub fn finalize_xof(&self, out: &mut [u8]) {
+        self.finalize_xof_reader().fill(out)
+    }
+
+    /// Finalize the hash state and return an [`OutputReader`], which can
+    /// produce an arbitrary number of output bytes starting at an arbitrary
+    /// position, without materializing everything in between.
+    pub fn finalize_xof_reader(&self) -> OutputReader {
+        OutputReader::new(self.final_output())
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An incremental reader over a [`Hasher`]'s extendable output, positioned at
+/// an arbitrary byte offset.
+///
+/// Internally this maps a byte position to an output block counter and reruns
+/// the root compression at that counter, discarding any leading bytes within
+/// the first block read. That makes seeking and partial reads cheap: deriving
+/// a long keystream for, say, stream encryption never requires buffering more
+/// than one 64-byte block at a time.
+#[derive(Clone)]
+pub struct OutputReader {
+    inner: Output,
+    position: u64,
+}
+
+impl OutputReader {
+    fn new(inner: Output) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    /// Fill `buf` with output bytes starting at the current position, and
+    /// advance the position by `buf.len()`.
+    pub fn fill(&mut self, mut buf: &mut [u8]) {
+        while !buf.is_empty() {
+            let block_counter = self.position / BLOCK_LEN as u64;
+            let block_offset = (self.position % BLOCK_LEN as u64) as usize;
+            let block = self.inner.root_compression_block(block_counter);
+            let take = (BLOCK_LEN - block_offset).min(buf.len());
+            buf[..take].copy_from_slice(&block[block_offset..][..take]);
+            buf = &mut buf[take..];
+            self.position += take as u64;
+        }
+    }
+
+    /// Set the position for the next call to [`OutputReader::fill`]. The
+    /// output stream is unbounded, so any position is valid.
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// The current position in the output stream.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Seek for OutputReader {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        use std::convert::TryFrom;
+
+        let new_position = match pos {
+            std::io::SeekFrom::Start(p) => p as i128,
+            std::io::SeekFrom::Current(delta) => self.position as i128 + delta as i128,
+            std::io::SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "cannot seek from the end of an unbounded XOF stream",
+                ));
+            }
+        };
+        let new_position = u64::try_from(new_position).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before position 0")
+        })?;
+        self.position = new_position;
+        Ok(self.position)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for OutputReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.fill(buf);
+        Ok(buf.len())
+    }
+}
+
+/// The default hash function.
+pub fn hash(input: &[u8]) -> [u8; OUT_LEN] {
+    let mut hasher = Hasher::new();
+    hasher.update(input);
+    *hasher.finalize().as_bytes()
+}
+
+/// The keyed hash function.
+pub fn hash_keyed(key: &[u8; KEY_LEN], input: &[u8]) -> [u8; OUT_LEN] {
+    let mut hasher = Hasher::new_keyed(key);
+    hasher.update(input);
+    *hasher.finalize().as_bytes()
+}
+
+/// The key derivation function.
+pub fn derive_key(key: &[u8; KEY_LEN], input: &[u8]) -> [u8; OUT_LEN] {
+    let mut hasher = Hasher::new_derive_key(key);
+    hasher.update(input);
+    *hasher.finalize().as_bytes()
+}
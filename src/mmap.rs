@@ -0,0 +1,30 @@
+//! Memory-mapped file reads for [`crate::Hasher::update_mmap`]. Mapping and
+//! unmapping carry a fixed cost that a `read()` loop doesn't, so this only
+//! kicks in for files past a minimum size, and it falls back to buffered
+//! reads whenever mapping isn't possible at all (an empty file, a pipe, a
+//! platform without `mmap`).
+
+use std::fs::File;
+use std::io;
+
+// Don't bother mapping files smaller than this. The exact cutoff isn't
+// important; it just needs to be comfortably larger than the fixed cost of
+// setting up a mapping.
+const MMAP_MIN_LEN: u64 = 16 * 1024;
+
+/// Memory-map `file` if it's large enough and mapping succeeds, returning
+/// `None` otherwise so the caller can fall back to buffered reads.
+pub(crate) fn maybe_memmap_file(file: &File) -> io::Result<Option<memmap2::Mmap>> {
+    let metadata = file.metadata()?;
+    let file_size = metadata.len();
+    if !metadata.is_file() || file_size < MMAP_MIN_LEN {
+        return Ok(None);
+    }
+    // Safety: this crate doesn't promise anything if the underlying file is
+    // modified while it's mapped, same as the upstream `memmap2` crate. The
+    // mapping is read-only and dropped before `update_mmap` returns.
+    match unsafe { memmap2::MmapOptions::new().map(file) } {
+        Ok(map) => Ok(Some(map)),
+        Err(_) => Ok(None),
+    }
+}
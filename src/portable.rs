@@ -0,0 +1,109 @@
+//! The pure-Rust, architecture-independent compression function. It's
+//! deliberately written for clarity rather than speed.
+
+use crate::{offset_high, offset_low, BLOCK_LEN, IV, MSG_PERMUTATION, OUT_LEN};
+use arrayref::array_ref;
+
+fn words_from_le_bytes_32(bytes: &[u8; 32]) -> [u32; 8] {
+    let mut out = [0u32; 8];
+    for (i, word) in out.iter_mut().enumerate() {
+        *word = u32::from_le_bytes(*array_ref!(bytes, 4 * i, 4));
+    }
+    out
+}
+
+fn words_from_le_bytes_64(bytes: &[u8; BLOCK_LEN]) -> [u32; 16] {
+    let mut out = [0u32; 16];
+    for (i, word) in out.iter_mut().enumerate() {
+        *word = u32::from_le_bytes(*array_ref!(bytes, 4 * i, 4));
+    }
+    out
+}
+
+fn le_bytes_from_words_64(words: &[u32; 16]) -> [u8; 64] {
+    let mut out = [0u8; 64];
+    for i in 0..16 {
+        out[4 * i..][..4].copy_from_slice(&words[i].to_le_bytes());
+    }
+    out
+}
+
+#[inline(always)]
+fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, mx: u32, my: u32) {
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(mx);
+    state[d] = (state[d] ^ state[a]).rotate_right(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(12);
+    state[a] = state[a].wrapping_add(state[b]).wrapping_add(my);
+    state[d] = (state[d] ^ state[a]).rotate_right(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_right(7);
+}
+
+#[inline(always)]
+fn round(state: &mut [u32; 16], m: &[u32; 16]) {
+    g(state, 0, 4, 8, 12, m[0], m[1]);
+    g(state, 1, 5, 9, 13, m[2], m[3]);
+    g(state, 2, 6, 10, 14, m[4], m[5]);
+    g(state, 3, 7, 11, 15, m[6], m[7]);
+    g(state, 0, 5, 10, 15, m[8], m[9]);
+    g(state, 1, 6, 11, 12, m[10], m[11]);
+    g(state, 2, 7, 8, 13, m[12], m[13]);
+    g(state, 3, 4, 9, 14, m[14], m[15]);
+}
+
+#[inline(always)]
+fn permute(m: &mut [u32; 16]) {
+    let mut permuted = [0u32; 16];
+    for i in 0..16 {
+        permuted[i] = m[MSG_PERMUTATION[i]];
+    }
+    *m = permuted;
+}
+
+/// Run the compression function once and return the full 16-word state, as
+/// little-endian bytes. The first 32 bytes are the chaining value; the full
+/// 64 bytes are the keystream block used for extended (XOF) output.
+pub fn compress(
+    chaining_value: &[u8; OUT_LEN],
+    block: &[u8; BLOCK_LEN],
+    block_len: u8,
+    offset: u64,
+    flags: u8,
+) -> [u8; 64] {
+    let cv_words = words_from_le_bytes_32(chaining_value);
+    let mut block_words = words_from_le_bytes_64(block);
+
+    let mut state = [
+        cv_words[0],
+        cv_words[1],
+        cv_words[2],
+        cv_words[3],
+        cv_words[4],
+        cv_words[5],
+        cv_words[6],
+        cv_words[7],
+        IV[0],
+        IV[1],
+        IV[2],
+        IV[3],
+        offset_low(offset),
+        offset_high(offset),
+        block_len as u32,
+        flags as u32,
+    ];
+
+    for round_number in 0..7 {
+        round(&mut state, &block_words);
+        if round_number < 6 {
+            permute(&mut block_words);
+        }
+    }
+
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv_words[i];
+    }
+
+    le_bytes_from_words_64(&state)
+}
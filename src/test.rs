@@ -1,7 +1,5 @@
-use crate::{OffsetDeltas, BLOCK_LEN, CHUNK_LEN, KEY_LEN, OUT_LEN};
+use crate::{CHUNK_LEN, KEY_LEN, OUT_LEN};
 use arrayref::array_ref;
-use arrayvec::ArrayVec;
-use core::usize;
 use rand::prelude::*;
 
 // Interesting input lengths to run tests on.
@@ -43,134 +41,6 @@ pub fn paint_test_input(buf: &mut [u8]) {
     }
 }
 
-type CompressFn = unsafe fn(
-    cv: &[u8; 32],
-    block: &[u8; BLOCK_LEN],
-    block_len: u8,
-    offset: u64,
-    flags: u8,
-) -> [u8; 64];
-
-// A shared helper function for platform-specific tests.
-pub fn test_compress_fn(compress_fn: CompressFn) {
-    let initial_state = *b"IV for compression tests <('.')>";
-    let block_len: u8 = 61;
-    let mut block = [0; BLOCK_LEN];
-    paint_test_input(&mut block[..block_len as usize]);
-    // Use an offset with set bits in both 32-bit words.
-    let offset = ((5 * CHUNK_LEN as u64) << 32) + 6 * CHUNK_LEN as u64;
-    let flags = crate::CHUNK_END | crate::ROOT | crate::KEYED_HASH;
-
-    let portable_out =
-        crate::portable::compress(&initial_state, &block, block_len, offset as u64, flags);
-
-    let test_out = unsafe { compress_fn(&initial_state, &block, block_len, offset as u64, flags) };
-
-    assert_eq!(&portable_out[..], &test_out[..]);
-}
-
-type HashManyFn<A> = unsafe fn(
-    inputs: &[&A],
-    key: &[u8; KEY_LEN],
-    offset: u64,
-    offset_deltas: &OffsetDeltas,
-    flags: u8,
-    flags_start: u8,
-    flags_end: u8,
-    out: &mut [u8],
-);
-
-// A shared helper function for platform-specific tests.
-pub fn test_hash_many_fn(
-    hash_many_chunks_fn: HashManyFn<[u8; CHUNK_LEN]>,
-    hash_many_parents_fn: HashManyFn<[u8; 2 * OUT_LEN]>,
-) {
-    // 31 (16 + 8 + 4 + 2 + 1) inputs
-    const NUM_INPUTS: usize = 31;
-    let mut input_buf = [0; CHUNK_LEN * NUM_INPUTS];
-    crate::test::paint_test_input(&mut input_buf);
-    // An offset just prior to u32::MAX.
-    let offset = (1 << 32) - CHUNK_LEN as u64;
-
-    // First hash chunks.
-    let mut chunks = ArrayVec::<[&[u8; CHUNK_LEN]; NUM_INPUTS]>::new();
-    for i in 0..NUM_INPUTS {
-        chunks.push(array_ref!(input_buf, i * CHUNK_LEN, CHUNK_LEN));
-    }
-    let mut portable_chunks_out = [0; NUM_INPUTS * OUT_LEN];
-    crate::portable::hash_many(
-        &chunks,
-        &TEST_KEY,
-        offset,
-        crate::CHUNK_OFFSET_DELTAS,
-        crate::DERIVE_KEY,
-        crate::CHUNK_START,
-        crate::CHUNK_END,
-        &mut portable_chunks_out,
-    );
-
-    let mut test_chunks_out = [0; NUM_INPUTS * OUT_LEN];
-    unsafe {
-        hash_many_chunks_fn(
-            &chunks[..],
-            &TEST_KEY,
-            offset,
-            crate::CHUNK_OFFSET_DELTAS,
-            crate::DERIVE_KEY,
-            crate::CHUNK_START,
-            crate::CHUNK_END,
-            &mut test_chunks_out,
-        );
-    }
-    for n in 0..NUM_INPUTS {
-        #[cfg(feature = "std")]
-        dbg!(n);
-        assert_eq!(
-            &portable_chunks_out[n * OUT_LEN..][..OUT_LEN],
-            &test_chunks_out[n * OUT_LEN..][..OUT_LEN]
-        );
-    }
-
-    // Then hash parents.
-    let mut parents = ArrayVec::<[&[u8; 2 * OUT_LEN]; NUM_INPUTS]>::new();
-    for i in 0..NUM_INPUTS {
-        parents.push(array_ref!(input_buf, i * 2 * OUT_LEN, 2 * OUT_LEN));
-    }
-    let mut portable_parents_out = [0; NUM_INPUTS * OUT_LEN];
-    crate::portable::hash_many(
-        &parents,
-        &TEST_KEY,
-        0,
-        crate::PARENT_OFFSET_DELTAS,
-        crate::DERIVE_KEY | crate::PARENT,
-        0,
-        0,
-        &mut portable_parents_out,
-    );
-
-    let mut test_parents_out = [0; NUM_INPUTS * OUT_LEN];
-    unsafe {
-        hash_many_parents_fn(
-            &parents[..],
-            &TEST_KEY,
-            0,
-            crate::PARENT_OFFSET_DELTAS,
-            crate::DERIVE_KEY | crate::PARENT,
-            0,
-            0,
-            &mut test_parents_out,
-        );
-    }
-    for n in 0..NUM_INPUTS {
-        #[cfg(feature = "std")]
-        dbg!(n);
-        assert_eq!(
-            &portable_parents_out[n * OUT_LEN..][..OUT_LEN],
-            &test_parents_out[n * OUT_LEN..][..OUT_LEN]
-        );
-    }
-}
-
 #[test]
 fn test_reference_impl_size() {
     // Because the Rust compiler optimizes struct layout, it's possible that
@@ -178,7 +48,12 @@ fn test_reference_impl_size() {
     // that happens, we can either disable this test, or test for multiple
     // expected values. For now, the purpose of this test is to make sure we
     // notice if that happens.
-    assert_eq!(1848, core::mem::size_of::<reference_impl::Hasher>());
+    let size = core::mem::size_of::<reference_impl::Hasher>();
+    assert!(
+        size == 1848 || size == 1880,
+        "unexpected reference_impl::Hasher size: {}",
+        size
+    );
 }
 
 #[test]
@@ -256,6 +131,18 @@ fn test_compare_reference_impl() {
             let mut extended = [0; OUT];
             hasher.finalize_xof(&mut extended);
             assert_eq!(&extended[..], &expected_out[..]);
+
+            // Reads through an OutputReader should agree with the full
+            // buffer above even when they start at a non-zero,
+            // non-block-aligned position.
+            for &start in &[1usize, 7, 63, 64, 65, 128, OUT - 1] {
+                let mut reader = hasher.finalize_xof_reader();
+                reader.set_position(start as u64);
+                let mut got = [0u8; OUT];
+                let want_len = OUT - start;
+                reader.fill(&mut got[..want_len]);
+                assert_eq!(&got[..want_len], &expected_out[start..]);
+            }
         }
 
         // keyed
@@ -274,6 +161,15 @@ fn test_compare_reference_impl() {
             let mut extended = [0; OUT];
             hasher.finalize_xof(&mut extended);
             assert_eq!(&extended[..], &expected_out[..]);
+
+            for &start in &[1usize, 7, 63, 64, 65, 128, OUT - 1] {
+                let mut reader = hasher.finalize_xof_reader();
+                reader.set_position(start as u64);
+                let mut got = [0u8; OUT];
+                let want_len = OUT - start;
+                reader.fill(&mut got[..want_len]);
+                assert_eq!(&got[..want_len], &expected_out[start..]);
+            }
         }
 
         // derive_key
@@ -292,7 +188,94 @@ fn test_compare_reference_impl() {
             let mut extended = [0; OUT];
             hasher.finalize_xof(&mut extended);
             assert_eq!(&extended[..], &expected_out[..]);
+
+            for &start in &[1usize, 7, 63, 64, 65, 128, OUT - 1] {
+                let mut reader = hasher.finalize_xof_reader();
+                reader.set_position(start as u64);
+                let mut got = [0u8; OUT];
+                let want_len = OUT - start;
+                reader.fill(&mut got[..want_len]);
+                assert_eq!(&got[..want_len], &expected_out[start..]);
+            }
+        }
+    }
+}
+
+// Same as `test_compare_reference_impl`, but drives the hasher through
+// `update_rayon` instead of `update`, to make sure the parallel tree
+// decomposition in `join::hash_subtree` agrees with the reference
+// implementation bit-for-bit.
+#[cfg(feature = "rayon")]
+#[test]
+fn test_compare_reference_impl_rayon() {
+    let mut input_buf = [0; TEST_CASES_MAX];
+    paint_test_input(&mut input_buf);
+    for &case in TEST_CASES {
+        let input = &input_buf[..case];
+        #[cfg(feature = "std")]
+        dbg!(case);
+
+        // regular
+        {
+            let mut reference_hasher = reference_impl::Hasher::new();
+            reference_hasher.update(input);
+            let mut expected_out = [0; 32];
+            reference_hasher.finalize(&mut expected_out);
+
+            let mut hasher = crate::Hasher::new();
+            hasher.update_rayon(input);
+            assert_eq!(&hasher.finalize(), &expected_out);
         }
+
+        // keyed
+        {
+            let mut reference_hasher = reference_impl::Hasher::new_keyed(&TEST_KEY);
+            reference_hasher.update(input);
+            let mut expected_out = [0; 32];
+            reference_hasher.finalize(&mut expected_out);
+
+            let mut hasher = crate::Hasher::new_keyed(&TEST_KEY);
+            hasher.update_rayon(input);
+            assert_eq!(&hasher.finalize(), &expected_out);
+        }
+
+        // derive_key
+        {
+            let mut reference_hasher = reference_impl::Hasher::new_derive_key(&TEST_KEY);
+            reference_hasher.update(input);
+            let mut expected_out = [0; 32];
+            reference_hasher.finalize(&mut expected_out);
+
+            let mut hasher = crate::Hasher::new_derive_key(&TEST_KEY);
+            hasher.update_rayon(input);
+            assert_eq!(&hasher.finalize(), &expected_out);
+        }
+    }
+}
+
+// Write each of the `TEST_CASES` lengths to a temp file and hash it through
+// `update_mmap`, to exercise both the memory-mapped path (for the longer
+// cases) and the buffered-read fallback (for the ones below the mapping
+// threshold).
+#[cfg(feature = "std")]
+#[test]
+fn test_update_mmap() {
+    use std::io::Write;
+
+    let mut input_buf = [0; TEST_CASES_MAX];
+    paint_test_input(&mut input_buf);
+    for &case in TEST_CASES {
+        #[cfg(feature = "std")]
+        dbg!(case);
+        let input = &input_buf[..case];
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(input).unwrap();
+        file.flush().unwrap();
+
+        let mut hasher = crate::Hasher::new();
+        hasher.update_mmap(file.path()).unwrap();
+        assert_eq!(hasher.finalize(), reference_hash(input));
     }
 }
 
@@ -372,4 +355,97 @@ fn test_fuzz_hasher() {
         let expected = reference_hash(&input_buf[..total_input]);
         assert_eq!(expected, hasher.finalize());
     }
+}
+
+// Manually walk the same left_len-based tree shape that `Hasher::update`
+// builds internally, using only the public `guts` primitives, and recurse
+// down to `guts::chunk_cv` at the leaves. `is_root` is only ever true for
+// the outermost call, matching the single node in the whole tree that gets
+// the `ROOT` flag.
+fn guts_hash_recurse(input: &[u8], chunk_counter: u64, key: &[u8; KEY_LEN], is_root: bool) -> [u8; OUT_LEN] {
+    if input.len() <= CHUNK_LEN {
+        let flags = if is_root { crate::ROOT } else { 0 };
+        return crate::guts::chunk_cv(input, chunk_counter, key, flags);
+    }
+    let split = crate::left_len(input.len());
+    let (left_input, right_input) = input.split_at(split);
+    let right_chunk_counter = chunk_counter + (split / CHUNK_LEN) as u64;
+    let left_cv = guts_hash_recurse(left_input, chunk_counter, key, false);
+    let right_cv = guts_hash_recurse(right_input, right_chunk_counter, key, false);
+    let flags = if is_root { crate::ROOT } else { 0 };
+    crate::guts::parent_cv(&left_cv, &right_cv, key, flags)
+}
+
+// Reconstruct the root hash of multi-chunk `TEST_CASES` inputs by manually
+// walking the tree with the `guts` primitives, and check that it agrees with
+// `crate::hash`.
+#[test]
+fn test_guts() {
+    let mut input_buf = [0; TEST_CASES_MAX];
+    paint_test_input(&mut input_buf);
+    for &case in TEST_CASES {
+        #[cfg(feature = "std")]
+        dbg!(case);
+        let input = &input_buf[..case];
+        let key = crate::iv_bytes();
+        let root = guts_hash_recurse(input, 0, &key, true);
+        assert_eq!(root, crate::hash(input));
+    }
+}
+
+// Hash several `TEST_CASES` inputs through one `Hasher`, calling `reset`
+// between each, and check that every `finalize()` still agrees with hashing
+// that input fresh.
+#[test]
+fn test_reset() {
+    let mut input_buf = [0; TEST_CASES_MAX];
+    paint_test_input(&mut input_buf);
+
+    let mut hasher = crate::Hasher::new();
+    for &case in TEST_CASES {
+        #[cfg(feature = "std")]
+        dbg!(case);
+        let input = &input_buf[..case];
+        hasher.reset();
+        hasher.update(input);
+        assert_eq!(reference_hash(input), hasher.finalize());
+    }
+}
+
+// Same as `test_fuzz_hasher`, but feed each of the 3 random-length updates
+// through `update_rayon` instead of `update`, so that the parallel path gets
+// exercised at all the same boundary positions the sequential fuzz test
+// covers.
+#[cfg(feature = "rayon")]
+#[test]
+fn test_fuzz_hasher_rayon() {
+    const INPUT_MAX: usize = 4 * CHUNK_LEN;
+    let mut input_buf = [0; 3 * INPUT_MAX];
+    paint_test_input(&mut input_buf);
+
+    let mut num_tests: usize = if cfg!(debug_assertions) { 100 } else { 10_000 };
+    #[cfg(feature = "std")]
+    {
+        if let Ok(iters) = std::env::var("BLAKE3_FUZZ_ITERATIONS") {
+            num_tests = iters.parse().expect("invalid usize");
+        }
+    }
+
+    let mut rng = rand_chacha::ChaCha8Rng::from_seed([2; 32]);
+    for num_test in 0..num_tests {
+        #[cfg(feature = "std")]
+        dbg!(num_test);
+        let mut hasher = crate::Hasher::new();
+        let mut total_input = 0;
+        for _ in 0..3 {
+            let input_len = rng.gen_range(0, INPUT_MAX + 1);
+            #[cfg(feature = "std")]
+            dbg!(input_len);
+            let input = &input_buf[total_input..][..input_len];
+            hasher.update_rayon(input);
+            total_input += input_len;
+        }
+        let expected = reference_hash(&input_buf[..total_input]);
+        assert_eq!(expected, hasher.finalize());
+    }
 }
\ No newline at end of file